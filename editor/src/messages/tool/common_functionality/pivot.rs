@@ -1,14 +1,58 @@
 //! Handler for the pivot overlay visible on the selected layer(s) whilst using the Select tool which controls the center of rotation/scale and origin of the layer.
 
 use super::graph_modification_utils;
-use crate::consts::PIVOT_DIAMETER;
+use crate::consts::{COLOR_OVERLAY_BLUE, PIVOT_DIAMETER};
+use crate::messages::portfolio::document::graph_operation::utility_types::TransformIn;
 use crate::messages::portfolio::document::overlays::utility_types::OverlayContext;
 use crate::messages::portfolio::document::utility_types::document_metadata::LayerNodeIdentifier;
 use crate::messages::prelude::*;
-use glam::{DAffine2, DVec2};
+use bezier_rs::TValue;
+use glam::{DAffine2, DMat2, DVec2};
 use graphene_std::transform::ReferencePoint;
 use std::collections::VecDeque;
 
+/// Largest change of the normalized pivot, on either axis, that [`Pivot::should_refresh_pivot_position`]
+/// still ignores; a larger change since the last refresh triggers a refresh of the tool options.
+const PIVOT_REFRESH_TOLERANCE: f64 = 1e-3;
+
+/// The eight elements of the symmetry group of the square (the dihedral group D4), used to flip and
+/// rotate a selection about the pivot by exact, discrete amounts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PivotSymmetry {
+	/// Leaves the selection unchanged.
+	Identity,
+	/// Rotation by 90° from +X toward +Y, which is clockwise on screen in the Y-down viewport.
+	Rotate90,
+	/// Rotation by 180°.
+	Rotate180,
+	/// Rotation by 270° from +X toward +Y (equivalently 90° counterclockwise on screen).
+	Rotate270,
+	/// Reflection across the horizontal axis through the pivot (flips top and bottom).
+	ReflectHorizontal,
+	/// Reflection across the vertical axis through the pivot (flips left and right).
+	ReflectVertical,
+	/// Reflection across the main diagonal through the pivot.
+	ReflectDiagonal,
+	/// Reflection across the anti-diagonal through the pivot.
+	ReflectAntidiagonal,
+}
+
+impl PivotSymmetry {
+	/// The orthogonal matrix for this symmetry element, expressed about the origin.
+	fn matrix(self) -> DMat2 {
+		match self {
+			Self::Identity => DMat2::IDENTITY,
+			Self::Rotate90 => DMat2::from_angle(std::f64::consts::FRAC_PI_2),
+			Self::Rotate180 => DMat2::from_angle(std::f64::consts::PI),
+			Self::Rotate270 => DMat2::from_angle(3. * std::f64::consts::FRAC_PI_2),
+			Self::ReflectHorizontal => DMat2::from_cols(DVec2::new(1., 0.), DVec2::new(0., -1.)),
+			Self::ReflectVertical => DMat2::from_cols(DVec2::new(-1., 0.), DVec2::new(0., 1.)),
+			Self::ReflectDiagonal => DMat2::from_cols(DVec2::new(0., 1.), DVec2::new(1., 0.)),
+			Self::ReflectAntidiagonal => DMat2::from_cols(DVec2::new(0., -1.), DVec2::new(-1., 0.)),
+		}
+	}
+}
+
 #[derive(Clone, Debug)]
 pub struct Pivot {
 	/// Pivot between (0,0) and (1,1)
@@ -19,6 +63,11 @@ pub struct Pivot {
 	pivot: Option<DVec2>,
 	/// The old pivot position in the GUI, used to reduce refreshes of the document bar
 	old_pivot_position: ReferencePoint,
+	/// The continuous normalized pivot at the last refresh, so sub-grid changes also trigger a refresh
+	old_normalized_pivot: DVec2,
+	/// The candidate point the pivot is currently snapping to, highlighted as an overlay for one frame
+	/// after each drag update and then cleared, so the highlight never outlives the drag.
+	snap_target: Option<DVec2>,
 	/// Used to enable and disable the pivot
 	active: bool,
 }
@@ -30,6 +79,8 @@ impl Default for Pivot {
 			transform_from_normalized: Default::default(),
 			pivot: Default::default(),
 			old_pivot_position: ReferencePoint::Center,
+			old_normalized_pivot: DVec2::splat(0.5),
+			snap_target: None,
 			active: true,
 		}
 	}
@@ -51,17 +102,20 @@ impl Pivot {
 			return;
 		}
 
-		let selected_nodes = document.network_interface.selected_nodes();
-		let mut layers = selected_nodes.selected_visible_and_unlocked_layers(&document.network_interface);
-		let Some(first) = layers.next() else {
+		let selected_layers: Vec<LayerNodeIdentifier> = document
+			.network_interface
+			.selected_nodes()
+			.selected_visible_and_unlocked_layers(&document.network_interface)
+			.collect();
+		let Some(&first) = selected_layers.first() else {
 			// If no layers are selected then we revert things back to default
 			self.normalized_pivot = DVec2::splat(0.5);
 			self.pivot = None;
+			self.snap_target = None;
 			return;
 		};
 
-		// Add one because the first item is consumed above.
-		let selected_layers_count = layers.count() + 1;
+		let selected_layers_count = selected_layers.len();
 
 		// If just one layer is selected we can use its inner transform (as it accounts for rotation)
 		if selected_layers_count == 1 {
@@ -70,21 +124,33 @@ impl Pivot {
 			self.transform_from_normalized = Self::get_layer_pivot_transform(first, document);
 			self.pivot = Some(self.transform_from_normalized.transform_point2(normalized_pivot));
 		} else {
-			// If more than one layer is selected we use the AABB with the mean of the pivots
-			let xy_summation = document
-				.network_interface
-				.selected_nodes()
-				.selected_visible_and_unlocked_layers(&document.network_interface)
-				.map(|layer| graph_modification_utils::get_viewport_pivot(layer, &document.network_interface))
-				.reduce(|a, b| a + b)
-				.unwrap_or_default();
-
-			let pivot = xy_summation / selected_layers_count as f64;
-			self.pivot = Some(pivot);
+			// If more than one layer is selected we use the AABB, rederiving the transform from the live
+			// bounding box so that a persisted pivot stays correct even if the layers have since moved.
 			let [min, max] = document.selected_visible_and_unlock_layers_bounding_box_viewport().unwrap_or([DVec2::ZERO, DVec2::ONE]);
-			self.normalized_pivot = (pivot - min) / (max - min);
-
 			self.transform_from_normalized = DAffine2::from_translation(min) * DAffine2::from_scale(max - min);
+
+			// Consult the document-stored override, which the accessor returns only when it was persisted
+			// for exactly this selection (so it survives undo/redo and reload without leaking across selections).
+			let normalized_override = document.group_pivot_override(&selected_layers);
+
+			if let Some(normalized_pivot) = normalized_override {
+				// Honor a pivot the user deliberately placed, rederiving its viewport position from the live box.
+				self.normalized_pivot = normalized_pivot;
+				self.pivot = Some(self.transform_from_normalized.transform_point2(normalized_pivot));
+			} else {
+				// Otherwise fall back to the mean of each layer's pivot.
+				let xy_summation = document
+					.network_interface
+					.selected_nodes()
+					.selected_visible_and_unlocked_layers(&document.network_interface)
+					.map(|layer| graph_modification_utils::get_viewport_pivot(layer, &document.network_interface))
+					.reduce(|a, b| a + b)
+					.unwrap_or_default();
+
+				let pivot = xy_summation / selected_layers_count as f64;
+				self.pivot = Some(pivot);
+				self.normalized_pivot = (pivot - min) / (max - min);
+			}
 		}
 	}
 
@@ -100,6 +166,11 @@ impl Pivot {
 		if let (Some(pivot), Some(data)) = (self.pivot, draw_data) {
 			overlay_context.pivot(pivot, data.0);
 		}
+		// Highlight the candidate the pivot will snap to so the user sees the target before releasing. The
+		// target is consumed here so a stale highlight can never outlive the drag that set it.
+		if let Some(snap_target) = self.snap_target.take() {
+			overlay_context.circle(snap_target, PIVOT_DIAMETER / 2., None, Some(COLOR_OVERLAY_BLUE));
+		}
 	}
 
 	/// Answers if the pivot widget has changed (so we should refresh the tool bar at the top of the canvas).
@@ -108,9 +179,15 @@ impl Pivot {
 			return false;
 		}
 
+		// Refresh on either a change of reference-grid slot or a sub-grid change of the continuous pivot,
+		// so moves that stay within one grid slot are refreshed too. The baseline only advances on a refresh,
+		// so a slow drag of many small steps still refreshes once it has moved past the tolerance in total.
 		let new = self.to_pivot_position();
-		let should_refresh = new != self.old_pivot_position;
-		self.old_pivot_position = new;
+		let should_refresh = new != self.old_pivot_position || self.normalized_pivot.abs_diff_eq(self.old_normalized_pivot, PIVOT_REFRESH_TOLERANCE).not();
+		if should_refresh {
+			self.old_pivot_position = new;
+			self.old_normalized_pivot = self.normalized_pivot;
+		}
 		should_refresh
 	}
 
@@ -118,13 +195,94 @@ impl Pivot {
 		self.normalized_pivot.into()
 	}
 
-	/// Sets the viewport position of the pivot for all selected layers.
-	pub fn set_viewport_position(&self, position: DVec2, document: &DocumentMessageHandler, responses: &mut VecDeque<Message>) {
+	/// The continuous normalized pivot position, not collapsed to the 3×3 reference grid as
+	/// [`Self::to_pivot_position`] does. This module does not display it; it can be set via [`Self::set_normalized_position`].
+	pub fn normalized_pivot(&self) -> DVec2 {
+		self.normalized_pivot
+	}
+
+	/// The current pivot position in viewspace, if a selection is present.
+	pub fn pivot(&self) -> Option<DVec2> {
+		self.pivot
+	}
+
+	/// Collects the geometric features the pivot can snap to in viewspace: the nine `ReferencePoint`
+	/// handles of the selection bounding box, the segment midpoints, and the vector anchor vertices of
+	/// the selected layers.
+	fn snap_candidates(&self, document: &DocumentMessageHandler) -> Vec<DVec2> {
+		let mut candidates = Vec::new();
+
+		// The nine reference-point handles of the selection bounding box.
+		for y in [0., 0.5, 1.] {
+			for x in [0., 0.5, 1.] {
+				candidates.push(self.transform_from_normalized.transform_point2(DVec2::new(x, y)));
+			}
+		}
+
+		// The anchors and segment midpoints of each selected vector layer.
+		for layer in document.network_interface.selected_nodes().selected_visible_and_unlocked_layers(&document.network_interface) {
+			let Some(subpaths) = graph_modification_utils::get_subpaths(layer, &document.network_interface) else {
+				continue;
+			};
+			let transform = document.metadata().transform_to_viewport(layer);
+			for subpath in subpaths {
+				candidates.extend(subpath.manipulator_groups().iter().map(|group| transform.transform_point2(group.anchor)));
+				candidates.extend(subpath.iter().map(|bezier| transform.transform_point2(bezier.evaluate(TValue::Parametric(0.5)))));
+			}
+		}
+
+		candidates
+	}
+
+	/// Snaps the raw pointer position to the closest snap candidate within half of [`PIVOT_DIAMETER`], recording
+	/// the chosen target so it can be highlighted as an overlay. Returns the position unchanged when
+	/// nothing is close enough.
+	fn snap_position(&mut self, position: DVec2, document: &DocumentMessageHandler) -> DVec2 {
+		let tolerance_squared = (PIVOT_DIAMETER / 2.).powi(2);
+		self.snap_target = self
+			.snap_candidates(document)
+			.into_iter()
+			.map(|candidate| (candidate, position.distance_squared(candidate)))
+			.filter(|&(_, distance_squared)| distance_squared < tolerance_squared)
+			.min_by(|(_, a), (_, b)| a.total_cmp(b))
+			.map(|(candidate, _)| candidate);
+
+		self.snap_target.unwrap_or(position)
+	}
+
+	/// Sets the viewport position of the pivot for all selected layers, snapping to a nearby geometric
+	/// feature when one is within tolerance.
+	pub fn set_viewport_position(&mut self, position: DVec2, document: &DocumentMessageHandler, responses: &mut VecDeque<Message>) {
 		if !self.active {
 			return;
 		}
 
-		for layer in document.network_interface.selected_nodes().selected_visible_and_unlocked_layers(&document.network_interface) {
+		let position = self.snap_position(position, document);
+		self.commit_viewport_position(position, document, responses);
+	}
+
+	/// Writes the exact viewport position of the pivot to every selected layer, without snapping.
+	fn commit_viewport_position(&self, position: DVec2, document: &DocumentMessageHandler, responses: &mut VecDeque<Message>) {
+		let selected_layers: Vec<LayerNodeIdentifier> = document
+			.network_interface
+			.selected_nodes()
+			.selected_visible_and_unlocked_layers(&document.network_interface)
+			.collect();
+
+		// For a multi-layer selection the deliberate group pivot is persisted in the document, keyed to
+		// this exact selection. It is stored as normalized coordinates of the live bounding box (not the
+		// derived viewport point) so it stays correct as the layers move or resize, and because it lives in
+		// the document it survives deselect/reselect, undo/redo, and reload. A single layer keeps its pivot
+		// on the layer itself via the per-layer message below.
+		if selected_layers.len() > 1 && self.transform_from_normalized.matrix2.determinant().abs() > f64::EPSILON {
+			let normalized_pivot = self.transform_from_normalized.inverse().transform_point2(position);
+			responses.add(DocumentMessage::SetGroupPivotOverride {
+				layers: selected_layers.clone(),
+				normalized_pivot: Some(normalized_pivot),
+			});
+		}
+
+		for layer in selected_layers {
 			let transform = Self::get_layer_pivot_transform(layer, document);
 			// Only update the pivot when computed position is finite.
 			if transform.matrix2.determinant().abs() <= f64::EPSILON {
@@ -135,13 +293,43 @@ impl Pivot {
 		}
 	}
 
+	/// Transforms all selected layers about the current pivot by the given [`PivotSymmetry`] element.
+	///
+	/// The whole selection is treated as a rigid group: every layer is transformed by the same
+	/// `from_translation(pivot) * R * from_translation(-pivot)` in viewspace, so a multi-layer
+	/// selection rotates around the shared computed pivot rather than each layer around its own.
+	pub fn apply_symmetry(&self, symmetry: PivotSymmetry, document: &DocumentMessageHandler, responses: &mut VecDeque<Message>) {
+		if !self.active {
+			return;
+		}
+
+		let Some(pivot) = self.pivot else {
+			return;
+		};
+
+		let transform = DAffine2::from_translation(pivot) * DAffine2::from_mat2(symmetry.matrix()) * DAffine2::from_translation(-pivot);
+
+		for layer in document.network_interface.selected_nodes().selected_visible_and_unlocked_layers(&document.network_interface) {
+			responses.add(GraphOperationMessage::TransformChange {
+				layer,
+				transform,
+				transform_in: TransformIn::Viewport,
+				skip_rerender: false,
+			});
+		}
+	}
+
 	/// Set the pivot using the normalized transform that is set above.
+	///
+	/// Unlike a drag, this commits the exact requested position without snapping, so a typed value such
+	/// as `(0.5, 0.5)` — or one outside the bounding box, with components below 0 or above 1 — lands
+	/// precisely where asked.
 	pub fn set_normalized_position(&self, position: DVec2, document: &DocumentMessageHandler, responses: &mut VecDeque<Message>) {
 		if !self.active {
 			return;
 		}
 
-		self.set_viewport_position(self.transform_from_normalized.transform_point2(position), document, responses);
+		self.commit_viewport_position(self.transform_from_normalized.transform_point2(position), document, responses);
 	}
 
 	/// Answers if the pointer is currently positioned over the pivot.